@@ -1,7 +1,12 @@
-mod rsobj;
-use rsobj::mqtt_client_obj::MqttClientObj;
-use rsobj::async_logger_obj::AsyncLoggerObj;
+mod rscls;
+use rscls::mqtt_client::{MqttClient, MqttConnectionConfig};
+use rscls::async_logger::{ForwardConfig, LoggerConfig, LoggerStats, RemoteConfig, RemoteKind};
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast;
 
 #[derive(serde::Deserialize)]
 struct Config {
@@ -12,6 +17,74 @@ struct Config {
     log_retention_hours: i64,
     host: String,
     port: u16,
+    // 远程转发（可选）：配置后日志会在本地持久化之外，额外批量转发到
+    // ZincObserve 或 Elasticsearch 兼容后端
+    #[serde(default)]
+    remote_url: Option<String>,
+    #[serde(default)]
+    remote_kind: Option<String>, // "zo" | "es_bulk"
+    #[serde(default)]
+    remote_org: Option<String>, // zo 专用
+    #[serde(default = "default_remote_batch_size")]
+    remote_batch_size: usize,
+    #[serde(default = "default_remote_flush_ms")]
+    remote_flush_ms: u64,
+    #[serde(default)]
+    remote_auth_token: Option<String>,
+    #[serde(default)]
+    remote_basic_auth_user: Option<String>,
+    #[serde(default)]
+    remote_basic_auth_pass: Option<String>,
+    // Fluent Forward 转发（可选）：配置后日志会额外以 Forward 协议推送给 Fluentd/Fluent Bit
+    #[serde(default)]
+    forward_addr: Option<String>,
+    #[serde(default)]
+    forward_tag: Option<String>,
+    // 管理/统计端口，查询各话题的实时运行状态
+    #[serde(default = "default_api_addr")]
+    api: String,
+    // MQTT 协议层 keep-alive（PINGREQ 间隔），之前硬编码在 MqttClient::new 里。
+    // 这只是协议层心跳，不是 TCP 层 SO_KEEPALIVE——rumqttc 不暴露设置后者的入口，
+    // 见 MqttClient::new 的文档注释
+    #[serde(default = "default_keep_alive_secs")]
+    keep_alive_secs: u64,
+    // MQTT 协议版本：4 走默认的 v3.1.1 客户端，5 走 rumqttc::v5，
+    // 解锁 user properties / content-type 等 v5 专属的 PUBLISH 元数据
+    #[serde(default = "default_mqtt_version")]
+    mqtt_version: u8,
+    // 断线重连的指数退避：从 reconnect_base_ms 开始翻倍，封顶 reconnect_max_ms
+    #[serde(default = "default_reconnect_base_ms")]
+    reconnect_base_ms: u64,
+    #[serde(default = "default_reconnect_max_ms")]
+    reconnect_max_ms: u64,
+}
+
+fn default_api_addr() -> String {
+    "127.0.0.1:9000".to_string()
+}
+
+fn default_keep_alive_secs() -> u64 {
+    5
+}
+
+fn default_mqtt_version() -> u8 {
+    4
+}
+
+fn default_reconnect_base_ms() -> u64 {
+    500
+}
+
+fn default_reconnect_max_ms() -> u64 {
+    30_000
+}
+
+fn default_remote_batch_size() -> usize {
+    100
+}
+
+fn default_remote_flush_ms() -> u64 {
+    2000
 }
 
 impl Default for Config {
@@ -24,6 +97,112 @@ impl Default for Config {
             log_retention_hours: 0,
             host: "192.168.1.13".to_string(),
             port: 41883,
+            remote_url: None,
+            remote_kind: None,
+            remote_org: None,
+            remote_batch_size: default_remote_batch_size(),
+            remote_flush_ms: default_remote_flush_ms(),
+            remote_auth_token: None,
+            remote_basic_auth_user: None,
+            remote_basic_auth_pass: None,
+            forward_addr: None,
+            forward_tag: None,
+            api: default_api_addr(),
+            keep_alive_secs: default_keep_alive_secs(),
+            mqtt_version: default_mqtt_version(),
+            reconnect_base_ms: default_reconnect_base_ms(),
+            reconnect_max_ms: default_reconnect_max_ms(),
+        }
+    }
+}
+
+/// 根据 Config 中的 remote_* 字段组装远程转发配置；未配置 remote_url/remote_kind 时返回 None
+fn build_remote_config(config: &Config) -> Option<RemoteConfig> {
+    let url = config.remote_url.clone()?;
+    let kind = match config.remote_kind.as_deref()? {
+        "zo" => RemoteKind::Zo { org: config.remote_org.clone().unwrap_or_else(|| "default".to_string()) },
+        "es_bulk" => RemoteKind::EsBulk,
+        other => {
+            eprintln!("未知的 remote_kind: {}，已禁用远程转发", other);
+            return None;
+        }
+    };
+    let basic_auth = match (&config.remote_basic_auth_user, &config.remote_basic_auth_pass) {
+        (Some(user), Some(pass)) => Some((user.clone(), pass.clone())),
+        _ => None,
+    };
+    Some(RemoteConfig {
+        url,
+        kind,
+        batch_size: config.remote_batch_size,
+        flush_ms: config.remote_flush_ms,
+        auth_token: config.remote_auth_token.clone(),
+        basic_auth,
+    })
+}
+
+/// 根据 Config 中的 forward_* 字段组装 Fluent Forward 配置；未配置 forward_addr 时返回 None
+fn build_forward_config(config: &Config) -> Option<ForwardConfig> {
+    Some(ForwardConfig {
+        addr: config.forward_addr.clone()?,
+        tag: config.forward_tag.clone().unwrap_or_else(|| "qmtt".to_string()),
+    })
+}
+
+/// 单个话题的统计快照，供管理端口以 JSON 返回
+#[derive(serde::Serialize)]
+struct TopicStatsSnapshot {
+    topic: String,
+    messages: u64,
+    bytes: u64,
+    current_file: String,
+    file_index: usize,
+    seconds_since_last_write: i64,
+}
+
+fn snapshot_stats(registry: &HashMap<String, LoggerStats>) -> Vec<TopicStatsSnapshot> {
+    let now = chrono::Local::now().timestamp();
+    registry
+        .iter()
+        .map(|(topic, stats)| {
+            let last_write = stats.last_write_unix.load(Ordering::Relaxed);
+            TopicStatsSnapshot {
+                topic: topic.clone(),
+                messages: stats.messages.load(Ordering::Relaxed),
+                bytes: stats.bytes.load(Ordering::Relaxed),
+                current_file: stats.current_file.lock().unwrap().clone(),
+                file_index: stats.file_index.load(Ordering::Relaxed),
+                seconds_since_last_write: if last_write == 0 { -1 } else { now - last_write },
+            }
+        })
+        .collect()
+}
+
+/// 本地管理/统计端口：每次连接返回一份所有话题的 JSON 快照
+async fn run_admin_server(addr: String, registry: Arc<HashMap<String, LoggerStats>>) {
+    let listener = match tokio::net::TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Failed to bind admin socket {}: {}", addr, e);
+            return;
+        }
+    };
+    println!("Admin/stats socket listening on {}", addr);
+
+    loop {
+        match listener.accept().await {
+            Ok((mut stream, _)) => {
+                let registry = registry.clone();
+                tokio::spawn(async move {
+                    let body = serde_json::to_string(&snapshot_stats(&registry)).unwrap_or_else(|_| "[]".to_string());
+                    if let Err(e) = stream.write_all(body.as_bytes()).await {
+                        eprintln!("Admin socket write failed: {}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                eprintln!("Admin socket accept error: {}", e);
+            }
         }
     }
 }
@@ -33,7 +212,7 @@ fn load_config() -> Config {
     let manifest_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
     let config_path = manifest_dir.join("config.toml");
 
-    match std::fs::read_to_string(&config_path) {
+    let mut config = match std::fs::read_to_string(&config_path) {
         Ok(content) => toml::from_str(&content)
             .unwrap_or_else(|e| {
                 eprintln!("配置解析失败: {}，使用默认值", e);
@@ -43,28 +222,94 @@ fn load_config() -> Config {
             eprintln!("读取配置文件失败: {}，使用默认值", e);
             Config::default()
         }
+    };
+
+    // reconnect_base_ms 为 0 会让 next_backoff_delay 一直返回零延迟，
+    // 退化成 busy-loop，和这个重连策略本来要解决的问题一样
+    if config.reconnect_base_ms == 0 {
+        eprintln!("reconnect_base_ms 不能为 0，已调整为 1ms");
+        config.reconnect_base_ms = 1;
+    }
+
+    config
+}
+
+/// 以 base_ms 为起点、max_ms 为上限的指数退避，外加 0~延迟一半的抖动，
+/// 避免所有话题在 broker 恢复的同一时刻同时重连（雷群）
+fn next_backoff_delay(backoff_ms: u64, max_ms: u64) -> (Duration, u64) {
+    let jitter_seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64)
+        .unwrap_or(0);
+    let jitter_ms = jitter_seed % (backoff_ms / 2 + 1);
+    let delay = Duration::from_millis(backoff_ms + jitter_ms);
+    let next_backoff_ms = (backoff_ms * 2).min(max_ms);
+    (delay, next_backoff_ms)
+}
+
+/// 轮询一次事件，成功返回 true，失败时就地打印错误并返回 false。
+///
+/// 这个函数存在的唯一原因是让 select! 分支体只拿到一个纯 Send 的 `bool`：
+/// `client.poll_event()` 的 `Err` 是 `Box<dyn Error>`（不是 Send）。如果直接在
+/// `result = client.poll_event() => { match result { ... } }` 这个分支体里处理，
+/// select! 宏会把 `result`（整个 Result，不只是它内部的 `e`）当作这条分支生成器
+/// 状态的一部分，在分支体后续的嵌套退避/关闭 select! 期间保持"存活"——哪怕
+/// 手动 drop 掉 `e` 本身也不够，因为是 `result` 的生命周期在起作用，不是 `e` 的。
+/// 这会让整条 run_client 的 Future 变成非 Send，main 里 tokio::spawn 它时报
+/// E0277。把 poll+match 完整收在这个函数内部、只往外传一个 bool，就不存在
+/// 这个问题了。
+async fn poll_once(client: &mut MqttClient, topic: &str) -> bool {
+    match client.poll_event().await {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("Event error for {}: {:?}", topic, e);
+            false
+        }
     }
 }
 
 // 客户端运行函数（只负责循环，不需要处理创建失败）
-async fn run_client(mut client: MqttClientObj, topic: String) {
+async fn run_client(
+    mut client: MqttClient,
+    topic: String,
+    mut shutdown_rx: broadcast::Receiver<()>,
+    reconnect_base_ms: u64,
+    reconnect_max_ms: u64,
+) {
     if let Err(e) = client.subscribe(&topic).await {
         println!("Failed to subscribe to {}: {:?}", topic, e);
         return;
     }
     println!("Subscribed to topic: {}", topic);
 
-    // 获取 logger 的 Arc 引用（完全拥有，无需共享）
-    let logger: Arc<AsyncLoggerObj> = Arc::clone(&client.logger);
+    let mut backoff_ms = reconnect_base_ms;
 
-    // 轮询 MQTT 事件
+    // 轮询 MQTT 事件，收到关闭信号后停止订阅并断开连接
     loop {
-        match client.next_event().await {
-            Ok(event) => {
-                MqttClientObj::handle_event(event, logger.clone());
+        tokio::select! {
+            ok = poll_once(&mut client, &topic) => {
+                if ok {
+                    backoff_ms = reconnect_base_ms; // 事件成功，退避重置
+                } else {
+                    let (delay, next_backoff_ms) = next_backoff_delay(backoff_ms, reconnect_max_ms);
+                    backoff_ms = next_backoff_ms;
+                    println!("Reconnecting {} in {}ms", topic, delay.as_millis());
+                    tokio::select! {
+                        _ = tokio::time::sleep(delay) => {}
+                        _ = shutdown_rx.recv() => {
+                            println!("Shutting down MQTT client for {} during backoff", topic);
+                            let _ = client.disconnect().await;
+                            return;
+                        }
+                    }
+                }
             }
-            Err(e) => {
-                eprintln!("Event error for {}: {:?}", topic, e);
+            _ = shutdown_rx.recv() => {
+                println!("Shutting down MQTT client for {}", topic);
+                if let Err(e) = client.disconnect().await {
+                    eprintln!("Failed to disconnect {}: {:?}", topic, e);
+                }
+                break;
             }
         }
     }
@@ -77,28 +322,57 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // --- define
     let max_file_size = config.max_file_size_mb * 1024 * 1024; // MB 转字节
+    let remote_config = build_remote_config(&config);
+    let forward_config = build_forward_config(&config);
+
+    // 关闭广播信号：所有客户端循环和 logger 写入任务都订阅它，
+    // 收到信号后各自清空队列、落盘、断开连接，而不是被粗暴 abort
+    let (shutdown_tx, _) = broadcast::channel::<()>(1);
 
     // 为每个话题创建客户端，然后在独立任务中运行
     // 关键：先创建客户端，只 spawn 已成功的实例
     let mut handles = Vec::new();
+    let mut logger_handles = Vec::new();
+    let mut stats_registry: HashMap<String, LoggerStats> = HashMap::new();
     for topic in &config.topics {
-        let host = config.host.clone();
-        let port = config.port;
         let topic = topic.clone();
-        let log_dir = config.log_dir.clone();
-        let max_file_size = max_file_size;
-        let timeout_secs = config.timeout_secs;
-        let log_retention_hours = config.log_retention_hours;
+        let shutdown_tx = shutdown_tx.clone();
+        let reconnect_base_ms = config.reconnect_base_ms;
+        let reconnect_max_ms = config.reconnect_max_ms;
 
         // 为每个话题生成唯一的 client_id（topic 中的 / 转为 _）
         let client_id = format!("mqtt_subscriber_{}", topic.replace('/', "_"));
 
+        let conn_config = MqttConnectionConfig {
+            host: config.host.clone(),
+            port: config.port,
+            client_id,
+            keep_alive_secs: config.keep_alive_secs,
+            mqtt_version: config.mqtt_version,
+        };
+        let logger_config = LoggerConfig {
+            max_file_size,
+            base_dir: config.log_dir.clone(),
+            timeout_secs: config.timeout_secs,
+            log_retention_hours: config.log_retention_hours,
+            remote: remote_config.clone(),
+            forward: forward_config.clone(),
+        };
+
         // 在 spawn 之前创建客户端并订阅，只 spawn 已成功的运行实例
-        match MqttClientObj::new(&host, port, &client_id, &topic, &log_dir, max_file_size, timeout_secs, log_retention_hours) {
-            Ok(client) => {
+        match MqttClient::new(&topic, conn_config, logger_config, shutdown_tx.clone()) {
+            Ok((client, writer_handle)) => {
+                // 注册到统计注册表，供管理端口查询
+                stats_registry.insert(topic.clone(), client.stats.clone());
+
+                // 写入任务的 handle 单独留在 main 里等，不能随 client 一起被
+                // run_client 吞掉，否则 main 提前退出时它可能还在 drain/flush
+                logger_handles.push(writer_handle);
+
+                let shutdown_rx = shutdown_tx.subscribe();
                 // 只 spawn 已成功创建的客户端
                 let handle = tokio::spawn(async move {
-                    run_client(client, topic).await;
+                    run_client(client, topic, shutdown_rx, reconnect_base_ms, reconnect_max_ms).await;
                 });
                 handles.push(handle);
             }
@@ -113,12 +387,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("No MQTT clients created. Exiting.");
         return Ok(());
     }
+
+    tokio::spawn(run_admin_server(config.api.clone(), Arc::new(stats_registry)));
+
     println!("MQTT subscribers started. Press Ctrl+C to stop.");
     tokio::signal::ctrl_c().await.ok();
 
-    // 等待所有任务完成
+    // 广播关闭信号，然后等待各任务自行清空队列、落盘、断连退出
+    println!("Shutting down, draining queues and flushing logs...");
+    let _ = shutdown_tx.send(());
+
+    let shutdown_timeout = Duration::from_secs(10);
     for handle in handles {
-        handle.abort();
+        if tokio::time::timeout(shutdown_timeout, handle).await.is_err() {
+            eprintln!("Timed out waiting for a client task to shut down");
+        }
+    }
+
+    // 客户端任务退出后，logger 写入任务仍然可能在 drain rx / 落盘，
+    // 必须等它们真正结束，否则队列里排队的日志行会随进程退出丢失
+    for handle in logger_handles {
+        if tokio::time::timeout(shutdown_timeout, handle).await.is_err() {
+            eprintln!("Timed out waiting for a logger task to shut down");
+        }
     }
 
     Ok(())
@@ -1,7 +1,10 @@
 use tokio::fs::{OpenOptions, File, read_dir, remove_file};
 use tokio::io::{AsyncWriteExt, BufWriter};
-use std::sync::Arc;
-use tokio::sync::mpsc;
+use tokio::net::TcpStream;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, mpsc};
 use std::path::PathBuf;
 use chrono::{Local, DateTime};
 use std::time::Duration;
@@ -11,6 +14,87 @@ pub struct AsyncLogger {
     tx: mpsc::Sender<String>,
 }
 
+/// 某个话题的运行时统计信息，由写入任务更新，供管理端口只读查询
+/// （字段都是 Arc 包裹的原子量/锁，clone 后仍指向同一份数据）
+#[derive(Clone)]
+pub struct LoggerStats {
+    pub messages: Arc<AtomicU64>,
+    pub bytes: Arc<AtomicU64>,
+    pub last_write_unix: Arc<AtomicI64>,
+    pub current_file: Arc<Mutex<String>>,
+    pub file_index: Arc<AtomicUsize>,
+}
+
+impl LoggerStats {
+    fn new() -> Self {
+        Self {
+            messages: Arc::new(AtomicU64::new(0)),
+            bytes: Arc::new(AtomicU64::new(0)),
+            last_write_unix: Arc::new(AtomicI64::new(0)),
+            current_file: Arc::new(Mutex::new(String::new())),
+            file_index: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+/// 远程转发的后端类型
+#[derive(Clone)]
+pub enum RemoteKind {
+    /// ZincObserve：POST 到 `<remote_url>/api/<org>/<stream>/_json`
+    Zo { org: String },
+    /// Elasticsearch `_bulk` 协议
+    EsBulk,
+}
+
+/// 远程批量转发配置
+#[derive(Clone)]
+pub struct RemoteConfig {
+    pub url: String,
+    pub kind: RemoteKind,
+    pub batch_size: usize,
+    pub flush_ms: u64,
+    pub auth_token: Option<String>,
+    pub basic_auth: Option<(String, String)>,
+}
+
+/// Fluent Forward 协议转发配置
+#[derive(Clone)]
+pub struct ForwardConfig {
+    pub addr: String,
+    pub tag: String,
+}
+
+impl ForwardConfig {
+    /// tag 是配置的前缀，追加话题名作为完整 tag（Fluentd 习惯用 "." 分隔）
+    fn full_tag(&self, topic: &str) -> String {
+        format!("{}.{}", self.tag, topic)
+    }
+}
+
+/// 进入缓冲区的一条待转发记录：[unix 秒时间戳, {"ext":...,"raw":...}]
+type ForwardEntry = (i64, serde_json::Value);
+
+const FORWARD_BUFFER_CAP: usize = 10_000;
+const FORWARD_BACKOFF_BASE: Duration = Duration::from_millis(200);
+const FORWARD_BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// 把缓冲区里的记录编码成一个 Fluent Forward "Forward Mode" 消息 `[tag, entries, option]`
+/// 并写入连接，成功后清空缓冲区
+async fn flush_forward_buffer(stream: &mut TcpStream, tag: &str, buffer: &mut VecDeque<ForwardEntry>) -> std::io::Result<()> {
+    if buffer.is_empty() {
+        return Ok(());
+    }
+
+    let entries: Vec<&ForwardEntry> = buffer.iter().collect();
+    let option = serde_json::json!({ "size": entries.len() });
+    let message = (tag, &entries, option);
+    let bytes = rmp_serde::to_vec(&message).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    stream.write_all(&bytes).await?;
+    buffer.clear();
+    Ok(())
+}
+
 struct LogFile {
     writer: BufWriter<File>,
     current_size: usize,
@@ -18,6 +102,124 @@ struct LogFile {
     last_write_time: std::time::Instant,  // 记录上次写入时间
 }
 
+/// write_line 里不随每次调用变化的部分（目录、话题名、轮转阈值），
+/// 和 file_index/current_file 这类逐行演进的状态分开传，避免参数表无限变长
+#[derive(Clone, Copy)]
+struct LogFileParams<'a> {
+    dir_path: &'a PathBuf,
+    safe_topic_name: &'a str,
+    max_file_size: usize,
+    timeout: Duration,
+}
+
+/// 把一行写入当前日志文件，按需处理超时轮转/大小轮转/开新文件，并更新统计信息
+/// 抽出来是因为正常写入路径和关闭时的 drain 路径都需要这套逻辑
+async fn write_line(
+    line: &str,
+    params: &LogFileParams<'_>,
+    file_index: &mut usize,
+    current_file: &mut Option<LogFile>,
+    stats: &LoggerStats,
+) {
+    let LogFileParams { dir_path, safe_topic_name, max_file_size, timeout } = *params;
+    if let Err(e) = tokio::fs::create_dir_all(dir_path).await {
+        eprintln!("Failed to create log directory: {}", e);
+        return;
+    }
+
+    // 检查是否需要超时轮转（超过 timeout 没新数据则创建新文件）
+    if let Some(file) = current_file {
+        if file.last_write_time.elapsed() > timeout {
+            if let Err(e) = file.writer.flush().await {
+                eprintln!("Failed to flush on timeout: {}", e);
+            }
+            // 超时离线后重新连接，索引重置为 00 重新开始
+            *file_index = 0;
+            *current_file = None;
+            println!("[{}] 设备超时离线，创建新日志文件", Local::now().format("%Y-%m-%d %H:%M:%S"));
+        }
+    }
+
+    // 检查是否需要打开文件
+    if current_file.is_none() {
+        let timestamp = Local::now().format("%Y-%m-%d_%H-%M-%S");
+        let filename = format!("{}-{}-{:02}.jsonl", timestamp, safe_topic_name, file_index);
+        let filepath = dir_path.join(&filename);
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&filepath)
+            .await
+            .expect("Failed to open log file");
+
+        let metadata = file.metadata().await.expect("Failed to get metadata");
+        let current_size = metadata.len() as usize;
+
+        *current_file = Some(LogFile {
+            writer: BufWriter::new(file),
+            current_size,
+            file_index: *file_index,
+            last_write_time: std::time::Instant::now(),
+        });
+
+        *stats.current_file.lock().unwrap() = filename;
+        stats.file_index.store(*file_index, Ordering::Relaxed);
+    }
+
+    let line_size = line.len() + 1; // +1 for newline
+    let logfile = current_file.as_mut().unwrap();
+
+    // 检查是否需要文件轮转（大小超限）
+    if logfile.current_size + line_size >= max_file_size {
+        if let Err(e) = logfile.writer.flush().await {
+            eprintln!("Failed to flush file: {}", e);
+        }
+
+        // 索引达到 99 时重置为 0
+        *file_index = (*file_index + 1) % 100;
+        let new_timestamp = Local::now().format("%Y-%m-%d_%H-%M-%S");
+        let filename = format!("{}-{}-{:02}.jsonl", new_timestamp, safe_topic_name, file_index);
+        let filepath = dir_path.join(&filename);
+
+        let new_file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&filepath)
+            .await
+            .expect("Failed to create new log file");
+
+        logfile.writer = BufWriter::new(new_file);
+        logfile.current_size = 0;
+        logfile.file_index = *file_index;
+
+        *stats.current_file.lock().unwrap() = filename;
+        stats.file_index.store(*file_index, Ordering::Relaxed);
+    }
+
+    // 写入日志
+    if let Err(e) = logfile.writer.write_all(line.as_bytes()).await {
+        eprintln!("Failed to write to log file: {}", e);
+        return;
+    }
+    if let Err(e) = logfile.writer.write_all(b"\n").await {
+        eprintln!("Failed to write newline: {}", e);
+        return;
+    }
+
+    // 每次写入后刷新，确保数据不丢失
+    if let Err(e) = logfile.writer.flush().await {
+        eprintln!("Failed to flush: {}", e);
+    }
+
+    logfile.current_size += line_size;
+    logfile.last_write_time = std::time::Instant::now();  // 更新最后写入时间
+
+    stats.messages.fetch_add(1, Ordering::Relaxed);
+    stats.bytes.fetch_add(line_size as u64, Ordering::Relaxed);
+    stats.last_write_unix.store(Local::now().timestamp(), Ordering::Relaxed);
+}
+
 /// 解析文件名中的时间戳，格式：YYYY-MM-DD_HH-MM-SS-topic-NN.jsonl
 fn parse_log_filename(filename: &str) -> Option<DateTime<Local>> {
     // 匹配格式：2024-01-15_10-30-45_topic_name-00.jsonl
@@ -90,22 +292,107 @@ async fn cleanup_expired_logs(base_dir: &str, retention_hours: i64) {
     }
 }
 
+/// 将一批行打包发送到远程后端，失败时重试并退避，但从不阻塞本地写入
+/// （调用方负责把这个函数 spawn 成独立任务）
+async fn send_remote_batch(http_client: reqwest::Client, remote: RemoteConfig, topic: String, lines: Vec<String>) {
+    if lines.is_empty() {
+        return;
+    }
+
+    let body = match &remote.kind {
+        RemoteKind::Zo { org } => (
+            format!("{}/api/{}/{}/_json", remote.url.trim_end_matches('/'), org, topic),
+            format!("[{}]", lines.join(",")),
+        ),
+        RemoteKind::EsBulk => {
+            let mut ndjson = String::new();
+            for line in &lines {
+                ndjson.push_str(&format!(r#"{{"index":{{"_index":"{}"}}}}"#, topic));
+                ndjson.push('\n');
+                ndjson.push_str(line);
+                ndjson.push('\n');
+            }
+            (format!("{}/_bulk", remote.url.trim_end_matches('/')), ndjson)
+        }
+    };
+    let (url, body) = body;
+
+    let max_attempts = 3;
+    let mut delay = Duration::from_millis(200);
+
+    for attempt in 1..=max_attempts {
+        let mut req = http_client
+            .post(&url)
+            .header("Content-Type", "application/json")
+            .body(body.clone());
+
+        if let Some(token) = &remote.auth_token {
+            req = req.bearer_auth(token);
+        }
+        if let Some((user, pass)) = &remote.basic_auth {
+            req = req.basic_auth(user, Some(pass));
+        }
+
+        match req.send().await {
+            Ok(resp) if resp.status().is_success() => return,
+            Ok(resp) => {
+                eprintln!("Remote forward to {} rejected (attempt {}/{}): {}", url, attempt, max_attempts, resp.status());
+            }
+            Err(e) => {
+                eprintln!("Remote forward to {} failed (attempt {}/{}): {}", url, attempt, max_attempts, e);
+            }
+        }
+
+        if attempt < max_attempts {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+
+    eprintln!("Giving up on remote forward to {} after {} attempts ({} lines dropped remotely)", url, max_attempts, lines.len());
+}
+
+/// 单个话题的日志落盘 + 远程/Forward 转发配置。原先是 `AsyncLogger::with_config`
+/// 的一长串位置参数，chunk0-1/3/4/5/6 陆续往上加字段，堆到 8 个触发了
+/// clippy::too_many_arguments，这里收拢成一个结构体（topic 和 shutdown_rx
+/// 仍然单独传，它们是身份/管道而不是配置）
+pub struct LoggerConfig {
+    pub max_file_size: usize,
+    pub base_dir: String,
+    pub timeout_secs: u64,
+    pub log_retention_hours: i64,
+    pub remote: Option<RemoteConfig>,
+    pub forward: Option<ForwardConfig>,
+}
+
 impl AsyncLogger {
-    /// 创建日志写入器，指定最大文件大小和目录
-    /// timeout_secs: 超过多少秒没新数据则创建新日志文件
-    /// log_retention_hours: 日志保留小时数，0 表示不自动清理
-    pub fn with_config(topic: &str, max_file_size: usize, base_dir: &str, timeout_secs: u64, log_retention_hours: i64) -> Result<Self, Box<dyn std::error::Error>> {
+    /// 创建日志写入器，`config` 里的 timeout_secs 控制多少秒没新数据就创建新日志文件，
+    /// log_retention_hours 为 0 表示不自动清理，remote/forward 为 None 表示不转发
+    ///
+    /// 返回的 `LoggerStats` 句柄供调用方（`main`）收集进注册表，
+    /// 供管理端口查询本话题的运行时状态
+    ///
+    /// shutdown_rx: 收到关闭广播后，清空 rx 里剩余的行、落盘并退出，
+    /// 保证没有排队中的日志行被丢弃
+    ///
+    /// 返回的 `JoinHandle` 对应写入任务本身：调用方（`main`）必须把它和
+    /// 客户端任务的 handle 一起 `await`，否则进程可能在写入任务还在
+    /// drain/flush 的过程中就退出，丢失关闭时排队的日志行
+    pub fn with_config(topic: &str, config: LoggerConfig, mut shutdown_rx: broadcast::Receiver<()>) -> Result<(Self, LoggerStats, tokio::task::JoinHandle<()>), Box<dyn std::error::Error>> {
+        let LoggerConfig { max_file_size, base_dir, timeout_secs, log_retention_hours, remote, forward } = config;
         let (tx, mut rx) = mpsc::channel::<String>(100);
         let topic_name = topic.to_string();
         let max_file_size = Arc::new(max_file_size);
-        let base_dir = Arc::new(base_dir.to_string());
+        let base_dir = Arc::new(base_dir);
         let timeout = Duration::from_secs(timeout_secs);
+        let stats = LoggerStats::new();
 
         // 异步写入任务
         let base_dir_clone = base_dir.clone();
         let retention_hours_clone = log_retention_hours;
+        let stats_clone = stats.clone();
 
-        tokio::spawn(async move {
+        let writer_handle = tokio::spawn(async move {
             let mut current_file: Option<LogFile> = None;
             let mut file_index = 0usize;
 
@@ -124,103 +411,156 @@ impl AsyncLogger {
                 });
             }
 
-            while let Some(line) = rx.recv().await {
-                // 确保 logs 目录存在
-                let dir_path = PathBuf::from(base_dir.as_str());
-                if let Err(e) = tokio::fs::create_dir_all(&dir_path).await {
-                    eprintln!("Failed to create log directory: {}", e);
-                    continue;
-                }
+            // 远程转发状态：本地持久化始终是权威来源，远程失败不得影响它
+            let http_client = remote.as_ref().map(|_| reqwest::Client::new());
+            let mut remote_batch: Vec<String> = Vec::new();
+            // 每批远程转发都是单独 spawn 的后台任务（自带最多 3 次尝试），
+            // 关闭时必须把它们也 join 掉，否则一个还在重试中的批次会随进程
+            // 退出被静默丢弃
+            let mut remote_send_handles: Vec<tokio::task::JoinHandle<()>> = Vec::new();
+            let remote_flush_ms = remote.as_ref().map(|r| r.flush_ms).unwrap_or(60_000).max(1);
+            let mut remote_interval = tokio::time::interval(Duration::from_millis(remote_flush_ms));
+            remote_interval.tick().await; // 第一次 tick 立即触发，消耗掉它
+
+            // Fluent Forward 转发状态：保持一条长连接，断线后指数退避重连，
+            // 重连期间的记录先攒在有界缓冲区里，避免瞬时故障丢日志
+            let mut forward_stream: Option<TcpStream> = None;
+            let mut forward_buffer: VecDeque<ForwardEntry> = VecDeque::new();
+            let mut forward_backoff = FORWARD_BACKOFF_BASE;
+            let mut forward_next_attempt = std::time::Instant::now();
+
+            loop {
+                tokio::select! {
+                    maybe_line = rx.recv() => {
+                        let Some(line) = maybe_line else { break; };
+
+                        let dir_path = PathBuf::from(base_dir.as_str());
+                        let log_file_params = LogFileParams { dir_path: &dir_path, safe_topic_name: &safe_topic_name, max_file_size: *max_file_size, timeout };
+                        write_line(&line, &log_file_params, &mut file_index, &mut current_file, &stats_clone).await;
+
+                        // 本地持久化是权威来源；远程转发只是附加的，攒批发送
+                        if let (Some(remote), Some(http_client)) = (&remote, &http_client) {
+                            remote_batch.push(line.clone());
+                            if remote_batch.len() >= remote.batch_size {
+                                let batch = std::mem::take(&mut remote_batch);
+                                remote_send_handles.push(tokio::spawn(send_remote_batch(http_client.clone(), remote.clone(), safe_topic_name.clone(), batch)));
+                            }
+                        }
 
-                // 检查是否需要超时轮转（超过1秒没新数据则创建新文件）
-                if let Some(ref mut file) = current_file {
-                    if file.last_write_time.elapsed() > timeout {
-                        // 关闭当前文件，下次写入时创建新的
-                        if let Err(e) = file.writer.flush().await {
-                            eprintln!("Failed to flush on timeout: {}", e);
+                        // Fluent Forward 转发：同一份行数据额外推送到 Fluentd/Fluent Bit
+                        if let Some(fwd) = &forward {
+                            if forward_buffer.len() >= FORWARD_BUFFER_CAP {
+                                forward_buffer.pop_front();
+                                eprintln!("[Forward] Buffer full, dropping oldest entry");
+                            }
+                            let record: serde_json::Value = serde_json::from_str(&line)
+                                .unwrap_or_else(|_| serde_json::json!({ "raw": line }));
+                            forward_buffer.push_back((Local::now().timestamp(), record));
+
+                            if forward_stream.is_none() && std::time::Instant::now() >= forward_next_attempt {
+                                match TcpStream::connect(&fwd.addr).await {
+                                    Ok(stream) => {
+                                        println!("[Forward] Connected to {}", fwd.addr);
+                                        forward_stream = Some(stream);
+                                        forward_backoff = FORWARD_BACKOFF_BASE;
+                                    }
+                                    Err(e) => {
+                                        eprintln!("[Forward] Connect to {} failed: {}, retrying in {:?}", fwd.addr, e, forward_backoff);
+                                        forward_next_attempt = std::time::Instant::now() + forward_backoff;
+                                        forward_backoff = (forward_backoff * 2).min(FORWARD_BACKOFF_MAX);
+                                    }
+                                }
+                            }
+
+                            if let Some(stream) = forward_stream.as_mut() {
+                                let tag = fwd.full_tag(&safe_topic_name);
+                                if let Err(e) = flush_forward_buffer(stream, &tag, &mut forward_buffer).await {
+                                    eprintln!("[Forward] Write to {} failed: {}, reconnecting", fwd.addr, e);
+                                    forward_stream = None;
+                                    forward_next_attempt = std::time::Instant::now() + forward_backoff;
+                                    forward_backoff = (forward_backoff * 2).min(FORWARD_BACKOFF_MAX);
+                                }
+                            }
                         }
-                        // 超时离线后重新连接，索引重置为 00 重新开始
-                        file_index = 0;
-                        current_file = None;
-                        println!("[{}] 设备超时离线，创建新日志文件", Local::now().format("%Y-%m-%d %H:%M:%S"));
                     }
-                }
-
-                // 检查是否需要打开文件
-                if current_file.is_none() {
-                    // 在第一条消息到达时才获取当前时间
-                    let timestamp = Local::now().format("%Y-%m-%d_%H-%M-%S");
-                    let filename = format!("{}-{}-{:02}.jsonl", timestamp, safe_topic_name, file_index);
-                    let filepath = dir_path.join(&filename);
-
-                    let file = OpenOptions::new()
-                        .create(true)
-                        .append(true)
-                        .open(&filepath)
-                        .await
-                        .expect("Failed to open log file");
-
-                    let metadata = file.metadata().await.expect("Failed to get metadata");
-                    let current_size = metadata.len() as usize;
-
-                    current_file = Some(LogFile {
-                        writer: BufWriter::new(file),
-                        current_size,
-                        file_index,
-                        last_write_time: std::time::Instant::now(),
-                    });
-                }
-
-                let line_size = line.len() + 1; // +1 for newline
-                let logfile = current_file.as_mut().unwrap();
-
-                // 检查是否需要文件轮转（大小超限）
-                if logfile.current_size + line_size >= *max_file_size {
-                    // 刷新并关闭当前文件
-                    if let Err(e) = logfile.writer.flush().await {
-                        eprintln!("Failed to flush file: {}", e);
+                    _ = remote_interval.tick(), if remote.is_some() => {
+                        if !remote_batch.is_empty() {
+                            let batch = std::mem::take(&mut remote_batch);
+                            let remote = remote.clone().unwrap();
+                            let http_client = http_client.clone().unwrap();
+                            remote_send_handles.push(tokio::spawn(send_remote_batch(http_client, remote, safe_topic_name.clone(), batch)));
+                        }
                     }
+                    _ = shutdown_rx.recv() => {
+                        println!("[{}] 收到关闭信号，清空队列并落盘", safe_topic_name);
+                        let dir_path = PathBuf::from(base_dir.as_str());
+                        let log_file_params = LogFileParams { dir_path: &dir_path, safe_topic_name: &safe_topic_name, max_file_size: *max_file_size, timeout };
+                        while let Ok(line) = rx.try_recv() {
+                            write_line(&line, &log_file_params, &mut file_index, &mut current_file, &stats_clone).await;
+
+                            if remote.is_some() {
+                                remote_batch.push(line.clone());
+                            }
+                            if forward.is_some() {
+                                if forward_buffer.len() >= FORWARD_BUFFER_CAP {
+                                    forward_buffer.pop_front();
+                                    eprintln!("[Forward] Buffer full, dropping oldest entry");
+                                }
+                                let record: serde_json::Value = serde_json::from_str(&line)
+                                    .unwrap_or_else(|_| serde_json::json!({ "raw": line }));
+                                forward_buffer.push_back((Local::now().timestamp(), record));
+                            }
+                        }
+                        if let Some(file) = current_file.as_mut() {
+                            if let Err(e) = file.writer.flush().await {
+                                eprintln!("Failed to flush on shutdown: {}", e);
+                            }
+                        }
 
-                    // 索引达到 99 时重置为 0
-                    file_index = (file_index + 1) % 100;
-                    // 使用当前时间作为新文件名
-                    let new_timestamp = Local::now().format("%Y-%m-%d_%H-%M-%S");
-                    let filename = format!("{}-{}-{:02}.jsonl", new_timestamp, safe_topic_name, file_index);
-                    let filepath = dir_path.join(&filename);
-
-                    let new_file = OpenOptions::new()
-                        .create(true)
-                        .append(true)
-                        .open(&filepath)
-                        .await
-                        .expect("Failed to create new log file");
-
-                    logfile.writer = BufWriter::new(new_file);
-                    logfile.current_size = 0;
-                    logfile.file_index = file_index;
-                }
+                        // 本地落盘之外，攒在 remote_batch 里还没发出去的一批也要在退出前
+                        // 尽最大努力发出去，否则这批会随进程退出被静默丢弃
+                        if let (Some(remote), Some(http_client)) = (&remote, &http_client) {
+                            if !remote_batch.is_empty() {
+                                let batch = std::mem::take(&mut remote_batch);
+                                send_remote_batch(http_client.clone(), remote.clone(), safe_topic_name.clone(), batch).await;
+                            }
+                        }
 
-                // 写入日志
-                if let Err(e) = logfile.writer.write_all(line.as_bytes()).await {
-                    eprintln!("Failed to write to log file: {}", e);
-                    continue;
-                }
-                if let Err(e) = logfile.writer.write_all(b"\n").await {
-                    eprintln!("Failed to write newline: {}", e);
-                    continue;
-                }
+                        // 之前已经 spawn 出去、可能还在重试退避中的远程转发批次
+                        // 同样不能随 writer 任务退出被丢弃，逐个 join 等它们跑完
+                        for handle in remote_send_handles.drain(..) {
+                            if let Err(e) = handle.await {
+                                eprintln!("Remote forward task for {} panicked: {}", safe_topic_name, e);
+                            }
+                        }
 
-                // 每次写入后刷新，确保数据不丢失
-                if let Err(e) = logfile.writer.flush().await {
-                    eprintln!("Failed to flush: {}", e);
+                        // Fluent Forward 缓冲区同理：退出前尽最大努力把攒下的记录发出去，
+                        // 必要时补连一次，而不是让它们随进程退出被静默丢弃
+                        if let Some(fwd) = &forward {
+                            if !forward_buffer.is_empty() {
+                                if forward_stream.is_none() {
+                                    match TcpStream::connect(&fwd.addr).await {
+                                        Ok(stream) => forward_stream = Some(stream),
+                                        Err(e) => eprintln!("[Forward] Final connect to {} failed: {}", fwd.addr, e),
+                                    }
+                                }
+                                if let Some(stream) = forward_stream.as_mut() {
+                                    let tag = fwd.full_tag(&safe_topic_name);
+                                    if let Err(e) = flush_forward_buffer(stream, &tag, &mut forward_buffer).await {
+                                        eprintln!("[Forward] Final flush to {} failed: {}, {} buffered entries dropped", fwd.addr, e, forward_buffer.len());
+                                    }
+                                } else {
+                                    eprintln!("[Forward] No connection to {} at shutdown, {} buffered entries dropped", fwd.addr, forward_buffer.len());
+                                }
+                            }
+                        }
+                        break;
+                    }
                 }
-
-                logfile.current_size += line_size;
-                logfile.last_write_time = std::time::Instant::now();  // 更新最后写入时间
             }
         });
 
-        Ok(Self { tx })
+        Ok((Self { tx }, stats, writer_handle))
     }
 
     /// 异步记录 JSON 行
@@ -229,4 +569,3 @@ impl AsyncLogger {
         Ok(())
     }
 }
-
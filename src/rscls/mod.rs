@@ -0,0 +1,2 @@
+pub mod async_logger;
+pub mod mqtt_client;
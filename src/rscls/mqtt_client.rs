@@ -1,13 +1,45 @@
 use rumqttc::{AsyncClient, MqttOptions, QoS, Event, Packet};
+use rumqttc::v5;
+use rumqttc::v5::mqttbytes::v5::Packet as PacketV5;
+use base64::Engine;
 use std::time::Duration;
 use chrono::{Local, TimeZone};
 use std::sync::Arc;
-use super::async_logger::AsyncLogger;
+use tokio::sync::broadcast;
+use super::async_logger::{AsyncLogger, LoggerConfig, LoggerStats};
+
+/// v4 和 v5 在 rumqttc 里是两套互不相干的 client/eventloop/packet 类型，
+/// 没有公共 trait，所以用一个枚举把版本差异收在 MqttClient 内部，
+/// 对外（run_client）仍然是同一套 subscribe/disconnect/poll 接口
+enum Connection {
+    V4 {
+        client: AsyncClient,
+        // boxed: both event loops are large (v4 ~760 bytes, v5 ~1216), and
+        // leaving either one unboxed makes it the oversized variant that
+        // still trips clippy::large_enum_variant
+        eventloop: Box<rumqttc::EventLoop>,
+    },
+    V5 {
+        client: v5::AsyncClient,
+        eventloop: Box<v5::EventLoop>,
+    },
+}
 
 pub struct MqttClient {
-    client: AsyncClient,
-    eventloop: rumqttc::EventLoop,  // 完全拥有，无需 Arc
+    conn: Connection,
     pub logger: Arc<AsyncLogger>,
+    pub stats: LoggerStats,
+}
+
+/// 连接一个 broker 所需的参数，从 `MqttClient::new` 的一长串位置参数里收拢出来
+/// （和 `LoggerConfig` 一样，都是 chunk0-1/3/4/5/6 陆续加字段堆出来的
+/// clippy::too_many_arguments）
+pub struct MqttConnectionConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub keep_alive_secs: u64,
+    pub mqtt_version: u8,
 }
 
 // 允许 MqttClient 在任务间移动（每个任务完全拥有自己的实例，无共享）
@@ -16,73 +48,210 @@ unsafe impl Send for MqttClient {}
 unsafe impl Sync for MqttClient {}
 
 impl MqttClient {
-    pub fn new(host: &str, port: u16, client_id: &str, topic: &str, log_dir: &str, max_file_size: usize, timeout_secs: u64, log_retention_hours: i64) -> Result<Self, Box<dyn std::error::Error>> {
-        let mut mqttoptions = MqttOptions::new(client_id, host, port);
-        mqttoptions.set_keep_alive(Duration::from_secs(5));
-        let (client, eventloop) = AsyncClient::new(mqttoptions, 10);
-        let logger = Arc::new(AsyncLogger::with_config(topic, max_file_size, log_dir, timeout_secs, log_retention_hours)?);
-        Ok(Self { client, eventloop, logger })
+    /// keep_alive_secs 是 MQTT 协议层的 PINGREQ 间隔（原先硬编码为 5 秒）。
+    ///
+    /// 注意：这 *不是* TCP 层的 SO_KEEPALIVE。rumqttc 自己在内部建立并持有
+    /// 底层 TcpStream，没有暴露套接字句柄或 `NetworkOptions` 之类的钩子来设置
+    /// 它，所以半开连接目前只能靠这个协议层心跳（以及 chunk0-5 加的重连退避）
+    /// 兜底去发现——两者不是同一回事，PINGREQ/PINGRESP 丢失到判定超时之间仍有
+    /// 一个 keep_alive_secs 量级的延迟窗口。如果 rumqttc 后续版本开放了设置
+    /// SO_KEEPALIVE 的入口，应该在这里补上真正的套接字级配置。
+    ///
+    /// mqtt_version: 4 走 rumqttc 默认的 v3.1.1 客户端（沿用原有行为）；
+    /// 5 走 rumqttc::v5 模块，PUBLISH 的 user properties / content-type /
+    /// response-topic / correlation-data 会被合并进 `ext`（见 handle_event_v5）。
+    /// 其它取值按 4 处理并打印一条警告（`build_remote_config` 对未知
+    /// remote_kind 同样打印警告，但随后禁用远程转发，而不是回退到默认值）。
+    ///
+    /// conn_config/logger_config 分别收拢了连接参数和落盘/转发配置
+    /// （见 `MqttConnectionConfig`/`LoggerConfig` 的注释）。
+    ///
+    /// 返回值除了 `Self` 还带一个 `JoinHandle`，对应 logger 写入任务本身
+    /// （`AsyncLogger::with_config` 向上传递）。调用方（`main`）必须把它和
+    /// 客户端任务的 handle 一起 await，否则进程可能在写入任务还在
+    /// drain/flush 排队日志的过程中就退出。
+    pub fn new(topic: &str, conn_config: MqttConnectionConfig, logger_config: LoggerConfig, shutdown_tx: broadcast::Sender<()>) -> Result<(Self, tokio::task::JoinHandle<()>), Box<dyn std::error::Error>> {
+        let MqttConnectionConfig { host, port, client_id, keep_alive_secs, mqtt_version } = conn_config;
+        let (logger, stats, writer_handle) = AsyncLogger::with_config(topic, logger_config, shutdown_tx.subscribe())?;
+
+        let conn = match mqtt_version {
+            5 => {
+                let mut mqttoptions = v5::MqttOptions::new(&client_id, &host, port);
+                mqttoptions.set_keep_alive(Duration::from_secs(keep_alive_secs));
+                let (client, eventloop) = v5::AsyncClient::new(mqttoptions, 10);
+                Connection::V5 { client, eventloop: Box::new(eventloop) }
+            }
+            other => {
+                if other != 4 {
+                    eprintln!("未知的 mqtt_version: {}，按 4 处理", other);
+                }
+                let mut mqttoptions = MqttOptions::new(&client_id, &host, port);
+                mqttoptions.set_keep_alive(Duration::from_secs(keep_alive_secs));
+                let (client, eventloop) = AsyncClient::new(mqttoptions, 10);
+                Connection::V4 { client, eventloop: Box::new(eventloop) }
+            }
+        };
+
+        Ok((Self { conn, logger: Arc::new(logger), stats }, writer_handle))
     }
 
     pub async fn subscribe(&self, topic: &str) -> Result<(), Box<dyn std::error::Error>> {
-        self.client.subscribe(topic, QoS::AtLeastOnce).await?;
+        match &self.conn {
+            Connection::V4 { client, .. } => client.subscribe(topic, QoS::AtLeastOnce).await?,
+            Connection::V5 { client, .. } => client.subscribe(topic, v5::mqttbytes::QoS::AtLeastOnce).await?,
+        }
+        Ok(())
+    }
+
+    /// 优雅关闭：断开底层 MQTT 连接，停止事件循环
+    pub async fn disconnect(&self) -> Result<(), Box<dyn std::error::Error>> {
+        match &self.conn {
+            Connection::V4 { client, .. } => client.disconnect().await?,
+            Connection::V5 { client, .. } => client.disconnect().await?,
+        }
+        Ok(())
+    }
+
+    /// 轮询下一个事件并处理，按连接版本分派到 handle_event_v4 / handle_event_v5。
+    ///
+    /// 处理过程是 await 在轮询循环里的，不是 detached spawn：之前 spawn 出去的
+    /// 处理任务不受 run_client 的 select! 或 logger 写入任务的关闭 drain 约束，
+    /// 关闭时可能还有一条 PUBLISH 正在解析/log().await，而 writer 任务已经
+    /// drain 完 rx 并退出，导致这条行在 channel 之外、谁也等不到它，静默丢失。
+    /// 内联处理后，poll_event() 返回 Ok 就意味着这行已经进了 mpsc channel。
+    pub async fn poll_event(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        match &mut self.conn {
+            Connection::V4 { eventloop, .. } => {
+                let event = eventloop.poll().await?;
+                Self::handle_event_v4(event, self.logger.clone()).await;
+            }
+            Connection::V5 { eventloop, .. } => {
+                let event = eventloop.poll().await?;
+                Self::handle_event_v5(event, self.logger.clone()).await;
+            }
+        }
         Ok(())
     }
 
-    pub async fn next_event(&mut self) -> Result<Event, Box<dyn std::error::Error>> {
-        Ok(self.eventloop.poll().await?)
+    /// 从 payload JSON 里提取 timestamp 字段（支持秒级/毫秒级），格式化成可读时间
+    fn extract_timestamp(value: &serde_json::Value) -> Option<String> {
+        value.get("timestamp").and_then(|v| v.as_f64()).map(|ts| {
+            let secs = if ts > 1e11 { ts / 1000.0 } else { ts };
+            Local.timestamp_opt(secs as i64, 0).single()
+                .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_default()
+        })
     }
 
-    pub fn handle_event(event: Event, logger: Arc<AsyncLogger>) {
-        tokio::spawn(async move {
-            match event {
-                Event::Incoming(packet) => {
-                    match packet {
-                        Packet::Publish(publish) => {
-                            let payload_str = String::from_utf8_lossy(&publish.payload);
-                            let recv_timestamp = Local::now().format("%Y-%m-%d %H:%M:%S");
-                            println!("[{}] 订阅成功: {}", recv_timestamp, payload_str);
-
-                            // 解析 payload JSON，尝试提取 timestamp 字段
-                            let payload_value: Result<serde_json::Value, _> = serde_json::from_str(&payload_str);
-                            let (ext_part, raw_part) = match payload_value {
-                                Ok(value) => {
-                                    // 检查是否有 timestamp 字段
-                                    let timestamp_str = value.get("timestamp").and_then(|v| v.as_f64()).map(|ts| {
-                                        // 尝试秒级或毫秒级时间戳
-                                        let secs = if ts > 1e11 { ts / 1000.0 } else { ts };
-                                        Local.timestamp_opt(secs as i64, 0).single()
-                                            .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
-                                            .unwrap_or_default()
-                                    });
-                                    let ext = timestamp_str.map(|ts| format!(r#"{{"timestamp":"{}"}}"#, ts)).unwrap_or_else(|| "{}".to_string());
-                                    (ext, value.to_string())
+    /// 组装最终写入文件的 JSONL 行：`{"ext":{...},"raw":...}`
+    fn build_json_line(ext: serde_json::Map<String, serde_json::Value>, raw: String) -> String {
+        format!(r#"{{"ext":{},"raw":{}}}"#, serde_json::Value::Object(ext), raw)
+    }
+
+    async fn handle_event_v4(event: Event, logger: Arc<AsyncLogger>) {
+        match event {
+            Event::Incoming(packet) => {
+                match packet {
+                    Packet::Publish(publish) => {
+                        let payload_str = String::from_utf8_lossy(&publish.payload);
+                        let recv_timestamp = Local::now().format("%Y-%m-%d %H:%M:%S");
+                        println!("[{}] 订阅成功: {}", recv_timestamp, payload_str);
+
+                        // 解析 payload JSON，尝试提取 timestamp 字段
+                        let payload_value: Result<serde_json::Value, _> = serde_json::from_str(&payload_str);
+                        let (ext, raw_part) = match payload_value {
+                            Ok(value) => {
+                                let mut ext = serde_json::Map::new();
+                                if let Some(ts) = Self::extract_timestamp(&value) {
+                                    ext.insert("timestamp".to_string(), serde_json::Value::String(ts));
                                 }
-                                Err(_) => {
-                                    // JSON 解析失败，记录错误信息
-                                    let error_msg = format!(r#"{{"message":"json解析失败"}}"#);
-                                    ("{}".to_string(), error_msg)
+                                (ext, value.to_string())
+                            }
+                            Err(_) => {
+                                // JSON 解析失败，记录错误信息
+                                let error_msg = format!(r#"{{"message":"json解析失败"}}"#);
+                                (serde_json::Map::new(), error_msg)
+                            }
+                        };
+
+                        let json_line = Self::build_json_line(ext, raw_part);
+                        if let Err(e) = logger.log(&json_line).await {
+                            eprintln!("Failed to log message: {}", e);
+                        }
+                    }
+                    Packet::ConnAck(_) => {
+                        println!("[System] Connected to MQTT broker");
+                    }
+                    _ => {}
+                }
+            }
+            Event::Outgoing(_) => {}
+        }
+    }
+
+    async fn handle_event_v5(event: v5::Event, logger: Arc<AsyncLogger>) {
+        match event {
+            v5::Event::Incoming(packet) => {
+                match packet {
+                    PacketV5::Publish(publish) => {
+                        let payload_str = String::from_utf8_lossy(&publish.payload);
+                        let recv_timestamp = Local::now().format("%Y-%m-%d %H:%M:%S");
+                        println!("[{}] 订阅成功 (v5): {}", recv_timestamp, payload_str);
+
+                        let payload_value: Result<serde_json::Value, _> = serde_json::from_str(&payload_str);
+                        let (mut ext, raw_part) = match payload_value {
+                            Ok(value) => {
+                                let mut ext = serde_json::Map::new();
+                                if let Some(ts) = Self::extract_timestamp(&value) {
+                                    ext.insert("timestamp".to_string(), serde_json::Value::String(ts));
                                 }
-                            };
-
-                            // 组装新格式的 JSONL
-                            let json_line = format!(
-                                r#"{{"ext":{},"raw":{}}}"#,
-                                ext_part,
-                                raw_part
-                            );
-                            if let Err(e) = logger.log(&json_line).await {
-                                eprintln!("Failed to log message: {}", e);
+                                (ext, value.to_string())
+                            }
+                            Err(_) => {
+                                let error_msg = format!(r#"{{"message":"json解析失败"}}"#);
+                                (serde_json::Map::new(), error_msg)
+                            }
+                        };
+
+                        // v5 PUBLISH 的结构化元数据不丢弃，原样并入 ext：
+                        // 发布方可以借此附带设备 id、schema 版本等信息，而不用塞进 payload
+                        if let Some(properties) = &publish.properties {
+                            if !properties.user_properties.is_empty() {
+                                let user_props: serde_json::Map<String, serde_json::Value> = properties
+                                    .user_properties
+                                    .iter()
+                                    .map(|(k, v)| (k.clone(), serde_json::Value::String(v.clone())))
+                                    .collect();
+                                ext.insert("user_properties".to_string(), serde_json::Value::Object(user_props));
+                            }
+                            if let Some(content_type) = &properties.content_type {
+                                ext.insert("content_type".to_string(), serde_json::Value::String(content_type.clone()));
+                            }
+                            if let Some(response_topic) = &properties.response_topic {
+                                ext.insert("response_topic".to_string(), serde_json::Value::String(response_topic.clone()));
+                            }
+                            if let Some(correlation_data) = &properties.correlation_data {
+                                // correlation data 通常是不透明的二进制，lossy UTF-8 会把它
+                                // 搅成替换字符且无法还原；base64 编码是可逆的
+                                ext.insert(
+                                    "correlation_data_base64".to_string(),
+                                    serde_json::Value::String(base64::engine::general_purpose::STANDARD.encode(correlation_data)),
+                                );
                             }
                         }
-                        Packet::ConnAck(_) => {
-                            println!("[System] Connected to MQTT broker");
+
+                        let json_line = Self::build_json_line(ext, raw_part);
+                        if let Err(e) = logger.log(&json_line).await {
+                            eprintln!("Failed to log message: {}", e);
                         }
-                        _ => {}
                     }
+                    PacketV5::ConnAck(_) => {
+                        println!("[System] Connected to MQTT broker (v5)");
+                    }
+                    _ => {}
                 }
-                Event::Outgoing(_) => {}
             }
-        });
+            v5::Event::Outgoing(_) => {}
+        }
     }
 }